@@ -0,0 +1,251 @@
+use colored::*;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::job::JobArtifact;
+
+/// Caps how large a single artifact we'll copy off disk into the store, so
+/// one job can't fill the worker's disk with a single huge output.
+pub const MAX_ARTIFACT_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Root directory under which every job's artifacts live, one subdirectory
+/// per job id.
+pub const ARTIFACTS_ROOT: &str = "artifacts";
+
+/// Checks that `s` is safe to use as a single path segment under
+/// `ARTIFACTS_ROOT` — no `..`/`.` components, no embedded separators, no
+/// absolute paths. Both the artifact name a goodfile declares and the
+/// `job`/`name` segments of a download request go through this before they
+/// ever touch the filesystem.
+fn sanitize_component(s: &str) -> Option<&str> {
+    let mut components = Path::new(s).components();
+    match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(_)), None) => Some(s),
+        _ => None,
+    }
+}
+
+/// Resolves `job_id`/`name` to a path under `root`, rejecting anything that
+/// isn't a plain two-segment path (no traversal, no absolute paths) and
+/// double-checking the resolved path is actually contained in `root` once
+/// canonicalized, in case a symlink inside `root` tries to escape it.
+pub async fn resolve_within_root(root: &Path, job_id: &str, name: &str) -> io::Result<PathBuf> {
+    let job_id = sanitize_component(job_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid job id"))?;
+    let name = sanitize_component(name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid artifact name"))?;
+
+    let candidate = root.join(job_id).join(name);
+
+    let canonical_root = fs::canonicalize(root).await?;
+    let canonical_candidate = fs::canonicalize(&candidate).await?;
+    if !canonical_candidate.starts_with(&canonical_root) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "artifact path escapes the artifacts root",
+        ));
+    }
+
+    Ok(candidate)
+}
+
+/// Creates `artifacts/<job_id>/`, tolerating the directory already existing.
+pub async fn reserve_artifacts_dir(job_id: &str) -> io::Result<PathBuf> {
+    if let Err(e) = fs::create_dir(ARTIFACTS_ROOT).await {
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            return Err(e);
+        }
+    }
+
+    let dir = Path::new(ARTIFACTS_ROOT).join(job_id);
+    match fs::create_dir(&dir).await {
+        Ok(()) => Ok(dir),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(dir),
+        Err(e) => Err(e),
+    }
+}
+
+/// Resolves `pattern` (which may be a glob, e.g. `target/release/*.so`)
+/// against `work_dir`, copies every match into `artifacts_dir` as raw
+/// bytes, and returns one `JobArtifact` per match actually collected.
+/// Matches over `MAX_ARTIFACT_BYTES` are skipped rather than truncated.
+///
+/// `pattern` comes from a goodfile's `artifact(path, name)` call or a job's
+/// `outputs`, both untrusted relative to whoever signed the job request —
+/// a pattern like `../../../../etc/passwd` is rejected rather than
+/// collected, the same containment check `resolve_within_root` applies to
+/// artifact downloads.
+pub async fn collect(work_dir: &str, artifacts_dir: &Path, declared_name: &str, pattern: &str) -> Vec<JobArtifact> {
+    let canonical_work_dir = match fs::canonicalize(work_dir).await {
+        Ok(dir) => dir,
+        Err(e) => {
+            println!("{} Could not resolve work directory {}: {}", "❌".red(), work_dir, e);
+            return Vec::new();
+        }
+    };
+
+    let full_pattern = format!("{}/{}", work_dir, pattern);
+    let candidates: Vec<PathBuf> = match glob::glob(&full_pattern) {
+        Ok(paths) => paths.filter_map(Result::ok).collect(),
+        Err(e) => {
+            println!("{} Invalid artifact pattern {}: {}", "❌".red(), pattern, e);
+            return Vec::new();
+        }
+    };
+
+    let mut matches = Vec::new();
+    for candidate in candidates {
+        match fs::canonicalize(&candidate).await {
+            Ok(canonical) if canonical.starts_with(&canonical_work_dir) => matches.push(candidate),
+            Ok(canonical) => println!(
+                "{} Artifact pattern {} matched {}, which is outside the work directory — skipping",
+                "❌".red(),
+                pattern,
+                canonical.display()
+            ),
+            Err(e) => println!("{} Could not resolve artifact match {}: {}", "❌".red(), candidate.display(), e),
+        }
+    }
+
+    if matches.is_empty() {
+        println!("{} No files matched artifact pattern {}", "❌".red(), pattern);
+        return Vec::new();
+    }
+
+    let mut collected = Vec::new();
+    for (i, src) in matches.iter().enumerate() {
+        let name = if matches.len() == 1 {
+            declared_name.to_string()
+        } else {
+            format!("{}-{}", declared_name, i)
+        };
+
+        match collect_one(src, artifacts_dir, &name).await {
+            Ok(artifact) => {
+                println!("{} Artifact collected: {} ({} bytes)", "✅".green(), artifact.name, artifact.size);
+                collected.push(artifact);
+            }
+            Err(e) => println!("{} Error collecting artifact {}: {}", "❌".red(), name, e),
+        }
+    }
+    collected
+}
+
+async fn collect_one(src: &Path, artifacts_dir: &Path, name: &str) -> io::Result<JobArtifact> {
+    // `name` comes from a goodfile's `artifact(path, name)` call, which runs
+    // inside the job's own cloned repo — treat it as untrusted input and
+    // refuse anything that isn't a plain filename (no `..`, no `/`).
+    let name = sanitize_component(name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("artifact name {:?} must be a plain filename", name),
+        )
+    })?;
+
+    let metadata = fs::metadata(src).await?;
+    if metadata.len() > MAX_ARTIFACT_BYTES {
+        return Err(io::Error::other(format!(
+            "{} is {} bytes, over the {} byte limit",
+            name,
+            metadata.len(),
+            MAX_ARTIFACT_BYTES
+        )));
+    }
+
+    let bytes = fs::read(src).await?;
+    let content_hash = format!("{:x}", Sha256::digest(&bytes));
+
+    let dest = artifacts_dir.join(name);
+    fs::write(&dest, &bytes).await?;
+
+    let path = dest
+        .strip_prefix(ARTIFACTS_ROOT)
+        .unwrap_or(&dest)
+        .to_string_lossy()
+        .to_string();
+
+    Ok(JobArtifact {
+        name: name.to_string(),
+        path,
+        size: bytes.len() as u64,
+        content_hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(label: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("viaduct-artifacts-test-{}-{}", label, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn sanitize_component_accepts_a_plain_name() {
+        assert_eq!(sanitize_component("output.txt"), Some("output.txt"));
+    }
+
+    #[test]
+    fn sanitize_component_rejects_traversal() {
+        assert_eq!(sanitize_component("../../../../tmp/pwned.txt"), None);
+        assert_eq!(sanitize_component("../pwned.txt"), None);
+        assert_eq!(sanitize_component(".."), None);
+    }
+
+    #[test]
+    fn sanitize_component_rejects_nested_and_absolute_paths() {
+        assert_eq!(sanitize_component("sub/dir/file.txt"), None);
+        assert_eq!(sanitize_component("/etc/passwd"), None);
+    }
+
+    #[tokio::test]
+    async fn collect_one_rejects_a_traversal_name() {
+        let work_dir = scratch_dir("work");
+        let artifacts_dir = scratch_dir("artifacts");
+        fs::create_dir_all(&work_dir).await.unwrap();
+        fs::create_dir_all(&artifacts_dir).await.unwrap();
+        let src = work_dir.join("file.txt");
+        fs::write(&src, b"hello").await.unwrap();
+
+        let escape_target = format!("pwned-{}.txt", uuid::Uuid::new_v4());
+        let result = collect_one(&src, &artifacts_dir, &format!("../../../../tmp/{}", escape_target)).await;
+
+        assert!(result.is_err());
+        assert!(!std::env::temp_dir().join(&escape_target).exists());
+
+        let _ = fs::remove_dir_all(&work_dir).await;
+        let _ = fs::remove_dir_all(&artifacts_dir).await;
+    }
+
+    #[tokio::test]
+    async fn collect_rejects_a_pattern_that_escapes_the_work_dir() {
+        let root = scratch_dir("escape-root");
+        let work_dir = root.join("work");
+        fs::create_dir_all(&work_dir).await.unwrap();
+        fs::write(root.join("secret.txt"), b"leak me").await.unwrap();
+        let artifacts_dir = scratch_dir("escape-artifacts");
+        fs::create_dir_all(&artifacts_dir).await.unwrap();
+
+        let collected = collect(work_dir.to_str().unwrap(), &artifacts_dir, "leak", "../secret.txt").await;
+
+        assert!(collected.is_empty());
+
+        let _ = fs::remove_dir_all(&root).await;
+        let _ = fs::remove_dir_all(&artifacts_dir).await;
+    }
+
+    #[tokio::test]
+    async fn resolve_within_root_rejects_traversal_segments() {
+        let root = scratch_dir("root");
+        fs::create_dir_all(root.join("job1")).await.unwrap();
+        fs::write(root.join("job1").join("out.txt"), b"hi").await.unwrap();
+
+        assert!(resolve_within_root(&root, "../escape", "out.txt").await.is_err());
+        assert!(resolve_within_root(&root, "job1", "../../out.txt").await.is_err());
+        assert!(resolve_within_root(&root, "job1", "out.txt").await.is_ok());
+
+        let _ = fs::remove_dir_all(&root).await;
+    }
+}
@@ -0,0 +1,156 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::job::Job;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header carrying the hex-encoded HMAC-SHA256 of the raw request body,
+/// computed under one of the worker's configured pre-shared keys.
+pub const SIGNATURE_HEADER: &str = "X-Viaduct-Signature";
+
+/// Pre-shared keys the worker accepts signed requests from, loaded from
+/// `VIADUCT_PSKS` (comma-separated) at startup. With no configured key,
+/// every signed request is rejected.
+#[derive(Debug, Clone)]
+pub struct PreSharedKeys(Vec<String>);
+
+impl PreSharedKeys {
+    pub fn from_env() -> Self {
+        let raw = std::env::var("VIADUCT_PSKS").unwrap_or_default();
+        Self(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+
+    /// True if `signature_hex` is a valid HMAC-SHA256 of `body` under any
+    /// configured key. Every candidate is checked in constant time so a
+    /// mismatch on the first key doesn't leak timing about the others.
+    ///
+    /// `pub(crate)` so other signed routes (e.g. artifact downloads) can
+    /// reuse the same keys instead of re-deriving their own auth scheme.
+    pub(crate) fn verify(&self, body: &[u8], signature_hex: &str) -> bool {
+        let Some(given) = decode_hex(signature_hex) else {
+            return false;
+        };
+
+        self.0.iter().fold(false, |matched, psk| {
+            let Ok(mut mac) = HmacSha256::new_from_slice(psk.as_bytes()) else {
+                return matched;
+            };
+            mac.update(body);
+            let expected = mac.finalize().into_bytes();
+            matched | constant_time_eq(&expected, &given)
+        })
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// A `Job` whose raw request body has already passed HMAC verification
+/// against a configured pre-shared key. Extracting this instead of
+/// `web::Json<Job>` is what gives `/job` its auth check: an unsigned or
+/// mis-signed request is rejected with 401 before `process_job` ever runs.
+pub struct VerifiedJob(pub Job);
+
+impl FromRequest for VerifiedJob {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let body_fut = web::Bytes::from_request(&req, payload);
+
+        Box::pin(async move {
+            let body = body_fut.await?;
+
+            let psks = req.app_data::<web::Data<PreSharedKeys>>().ok_or_else(|| {
+                actix_web::error::ErrorInternalServerError("no pre-shared keys configured")
+            })?;
+
+            let signature = req
+                .headers()
+                .get(SIGNATURE_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| actix_web::error::ErrorUnauthorized("missing signature"))?;
+
+            if !psks.verify(&body, signature) {
+                return Err(actix_web::error::ErrorUnauthorized("invalid signature"));
+            }
+
+            let job: Job = serde_json::from_slice(&body)
+                .map_err(|e| actix_web::error::ErrorBadRequest(e.to_string()))?;
+
+            Ok(VerifiedJob(job))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(psk: &str, body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(psk.as_bytes()).unwrap();
+        mac.update(body);
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    #[test]
+    fn verifies_a_signature_from_a_configured_key() {
+        let psks = PreSharedKeys(vec!["topsecret".to_string()]);
+        let body = b"{\"name\":\"test\"}";
+        let signature = sign("topsecret", body);
+
+        assert!(psks.verify(body, &signature));
+    }
+
+    #[test]
+    fn rejects_a_signature_from_an_unconfigured_key() {
+        let psks = PreSharedKeys(vec!["topsecret".to_string()]);
+        let body = b"{\"name\":\"test\"}";
+        let signature = sign("wrongkey", body);
+
+        assert!(!psks.verify(body, &signature));
+    }
+
+    #[test]
+    fn rejects_a_signature_for_a_different_body() {
+        let psks = PreSharedKeys(vec!["topsecret".to_string()]);
+        let signature = sign("topsecret", b"{\"name\":\"test\"}");
+
+        assert!(!psks.verify(b"{\"name\":\"tampered\"}", &signature));
+    }
+
+    #[test]
+    fn rejects_malformed_hex() {
+        let psks = PreSharedKeys(vec!["topsecret".to_string()]);
+        assert!(!psks.verify(b"anything", "not-hex"));
+    }
+}
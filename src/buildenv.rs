@@ -0,0 +1,405 @@
+use mlua::{Lua, Table, Value, Variadic};
+use std::cell::RefCell;
+use std::process::Stdio;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::StreamEvent;
+
+/// Sends SIGKILL to the process group rooted at `pid`, taking out any
+/// descendants a shell step may have spawned along with it. Any caller that
+/// spawns with `process_group(0)` (`run()` here, and `execute_job`'s git
+/// clone) can use this the same way to clean up after a timeout.
+#[cfg(unix)]
+pub(crate) fn kill_process_group(pid: i32) {
+    unsafe {
+        libc::kill(-pid, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn kill_process_group(_pid: i32) {}
+
+/// One artifact registered by a goodfile via `artifact(path, name)`.
+#[derive(Debug, Clone)]
+pub struct DeclaredArtifact {
+    pub path: String,
+    pub name: String,
+}
+
+/// One metric registered by a goodfile via `metric(name, value)`.
+#[derive(Debug, Clone)]
+pub struct DeclaredMetric {
+    pub name: String,
+    pub value: String,
+}
+
+/// Default goodfile used when a repository doesn't commit its own. It just
+/// replays `job.commands` in order, which keeps old jobs working unchanged.
+pub const DEFAULT_GOODFILE: &str = r#"
+for _, cmd in ipairs(job.commands) do
+    run(cmd)
+end
+"#;
+
+/// Shared state that the `run`/`artifact`/`metric`/`dependencies` globals
+/// write into while a goodfile executes inside the Lua context.
+struct Shared {
+    work_dir: String,
+    artifacts: Vec<DeclaredArtifact>,
+    metrics: Vec<DeclaredMetric>,
+    dependencies: Vec<String>,
+    log_tx: UnboundedSender<StreamEvent>,
+    /// Index and text of the command currently being run, so a goodfile
+    /// failure can be attributed to a specific step. Cleared once that
+    /// step finishes successfully.
+    current_command: Option<(usize, String)>,
+    next_command_index: usize,
+    /// When the whole-build time budget (`Job.timeout_secs`) runs out.
+    /// `None` means no whole-build budget was set.
+    deadline: Option<Instant>,
+    /// Set when `exec` failed because a step ran past its budget (either
+    /// its own `run(cmd, {timeout=...})` override or the whole-build
+    /// deadline), as opposed to an ordinary nonzero exit. Timeouts are an
+    /// infrastructure-level failure, not a test result.
+    timed_out: bool,
+}
+
+/// Wraps an embedded Lua runtime seeded with the build primitives a
+/// goodfile needs (`run`, `artifact`, `metric`, `dependencies`) plus a
+/// `job` table exposing the job's declared inputs. Running a goodfile
+/// through a `BuildEnv` replaces the old hard-coded command loop.
+///
+/// `run` is backed by `tokio::process::Command`, so the goodfile's steps
+/// run as async subprocesses whose stdout/stderr are streamed line-by-line
+/// to `log_tx` as they're produced, instead of being buffered until the
+/// step exits.
+pub struct BuildEnv {
+    lua: Lua,
+    shared: Rc<RefCell<Shared>>,
+}
+
+/// Reads `reader` line-by-line and forwards each line to `log_tx`, prefixed
+/// with the command it came from so interleaved stdout/stderr stay legible.
+async fn stream_lines<R: AsyncRead + Unpin>(
+    reader: R,
+    tx: UnboundedSender<StreamEvent>,
+    cmd: String,
+) -> std::io::Result<()> {
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let _ = tx.send(StreamEvent::Log { line: format!("{} {}", cmd, line) });
+    }
+    Ok(())
+}
+
+impl BuildEnv {
+    /// Creates a fresh environment rooted at `work_dir`, with `inputs`
+    /// exposed to the goodfile as `job.inputs.<name>`. Log lines produced
+    /// by `run()` are forwarded to `log_tx` as the command executes.
+    pub fn new(
+        work_dir: &str,
+        inputs: &[crate::job::JobInput],
+        log_tx: UnboundedSender<StreamEvent>,
+        timeout_secs: Option<u64>,
+    ) -> mlua::Result<Self> {
+        let lua = Lua::new();
+        let shared = Rc::new(RefCell::new(Shared {
+            work_dir: work_dir.to_string(),
+            artifacts: Vec::new(),
+            metrics: Vec::new(),
+            dependencies: Vec::new(),
+            log_tx,
+            current_command: None,
+            next_command_index: 0,
+            deadline: timeout_secs.map(|secs| Instant::now() + Duration::from_secs(secs)),
+            timed_out: false,
+        }));
+
+        {
+            let globals = lua.globals();
+
+            let run_shared = shared.clone();
+            let run = lua.create_async_function(move |_, (cmd, opts): (String, Option<Table>)| {
+                let run_shared = run_shared.clone();
+                async move {
+                    let work_dir = run_shared.borrow().work_dir.clone();
+                    let log_tx = run_shared.borrow().log_tx.clone();
+
+                    {
+                        let mut shared = run_shared.borrow_mut();
+                        let index = shared.next_command_index;
+                        shared.next_command_index += 1;
+                        shared.current_command = Some((index, cmd.clone()));
+                    }
+
+                    let mut cwd = work_dir.clone();
+                    let mut command = Command::new("sh");
+                    command.arg("-c").arg(&cmd);
+
+                    let mut override_secs: Option<u64> = None;
+                    if let Some(opts) = &opts {
+                        if let Ok(Value::String(s)) = opts.get::<Value>("cwd") {
+                            cwd = format!("{}/{}", work_dir, s.to_str()?);
+                        }
+                        if let Ok(env_table) = opts.get::<Table>("env") {
+                            for pair in env_table.pairs::<String, String>() {
+                                let (k, v) = pair?;
+                                command.env(k, v);
+                            }
+                        }
+                        if let Ok(secs) = opts.get::<u64>("timeout") {
+                            override_secs = Some(secs);
+                        }
+                    }
+
+                    // The effective budget for this step is whichever runs
+                    // out first: its own override, or what's left of the
+                    // whole-build deadline.
+                    let remaining = run_shared
+                        .borrow()
+                        .deadline
+                        .map(|d| d.saturating_duration_since(Instant::now()));
+                    let budget = match (override_secs.map(Duration::from_secs), remaining) {
+                        (Some(o), Some(r)) => Some(o.min(r)),
+                        (Some(o), None) => Some(o),
+                        (None, r) => r,
+                    };
+
+                    command
+                        .current_dir(&cwd)
+                        .process_group(0)
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::piped());
+
+                    let mut child = command.spawn().map_err(|e| {
+                        mlua::Error::RuntimeError(format!("failed to spawn `{}`: {}", cmd, e))
+                    })?;
+                    let pid = child.id().map(|id| id as i32);
+
+                    let stdout = child.stdout.take().expect("piped stdout");
+                    let stderr = child.stderr.take().expect("piped stderr");
+
+                    let run_to_completion = async {
+                        let (stdout_res, stderr_res) = tokio::join!(
+                            stream_lines(stdout, log_tx.clone(), cmd.clone()),
+                            stream_lines(stderr, log_tx.clone(), cmd.clone()),
+                        );
+                        stdout_res.map_err(mlua::Error::external)?;
+                        stderr_res.map_err(mlua::Error::external)?;
+
+                        child.wait().await.map_err(|e| {
+                            mlua::Error::RuntimeError(format!("failed to wait on `{}`: {}", cmd, e))
+                        })
+                    };
+
+                    let status = match budget {
+                        Some(budget) => match tokio::time::timeout(budget, run_to_completion).await {
+                            Ok(result) => result?,
+                            Err(_) => {
+                                if let Some(pid) = pid {
+                                    kill_process_group(pid);
+                                }
+                                run_shared.borrow_mut().timed_out = true;
+                                return Err(mlua::Error::RuntimeError(format!(
+                                    "`{}` timed out after {}s",
+                                    cmd,
+                                    budget.as_secs()
+                                )));
+                            }
+                        },
+                        None => run_to_completion.await?,
+                    };
+
+                    if !status.success() {
+                        return Err(mlua::Error::RuntimeError(format!(
+                            "`{}` exited with {}",
+                            cmd, status
+                        )));
+                    }
+
+                    run_shared.borrow_mut().current_command = None;
+                    Ok(())
+                }
+            })?;
+            globals.set("run", run)?;
+
+            let artifact_shared = shared.clone();
+            let artifact = lua.create_function(move |_, (path, name): (String, String)| {
+                artifact_shared
+                    .borrow_mut()
+                    .artifacts
+                    .push(DeclaredArtifact { path, name });
+                Ok(())
+            })?;
+            globals.set("artifact", artifact)?;
+
+            let metric_shared = shared.clone();
+            let metric = lua.create_function(move |_, (name, value): (String, Value)| {
+                let value = match value {
+                    Value::String(s) => s.to_str()?.to_string(),
+                    other => format!("{:?}", other),
+                };
+                metric_shared
+                    .borrow_mut()
+                    .metrics
+                    .push(DeclaredMetric { name, value });
+                Ok(())
+            })?;
+            globals.set("metric", metric)?;
+
+            let deps_shared = shared.clone();
+            let dependencies = lua.create_function(move |_, toolchains: Variadic<String>| {
+                deps_shared.borrow_mut().dependencies.extend(toolchains);
+                Ok(())
+            })?;
+            globals.set("dependencies", dependencies)?;
+
+            let job_table = lua.create_table()?;
+            let inputs_table = lua.create_table()?;
+            for input in inputs {
+                inputs_table.set(input.name.clone(), input.value.clone())?;
+            }
+            job_table.set("inputs", inputs_table)?;
+            globals.set("job", job_table)?;
+        }
+
+        Ok(Self { lua, shared })
+    }
+
+    /// Exposes `commands` to the goodfile as `job.commands`, so the fallback
+    /// script (and any goodfile that wants to) can still drive the job's
+    /// static command list. Call it before `exec`.
+    pub fn set_commands(&self, commands: &[String]) -> mlua::Result<()> {
+        let globals = self.lua.globals();
+        let job_table: Table = globals.get("job")?;
+        let commands_table = self.lua.create_table()?;
+        for (i, cmd) in commands.iter().enumerate() {
+            commands_table.set(i + 1, cmd.clone())?;
+        }
+        job_table.set("commands", commands_table)?;
+        Ok(())
+    }
+
+    /// Runs the goodfile. A Lua error here (from `run` failing, or a syntax
+    /// error in the script itself) should be surfaced as a failed job.
+    pub async fn exec(&self, source: &str) -> mlua::Result<()> {
+        self.lua.load(source).exec_async().await
+    }
+
+    pub fn artifacts(&self) -> Vec<DeclaredArtifact> {
+        self.shared.borrow().artifacts.clone()
+    }
+
+    /// The index and text of the command that was running when `exec`
+    /// returned an error, if the error came from a failed `run()` step
+    /// rather than from the goodfile itself (a syntax error, a bad global).
+    pub fn failing_command(&self) -> Option<(usize, String)> {
+        self.shared.borrow().current_command.clone()
+    }
+
+    /// True if the last `exec` failure was a step running past its time
+    /// budget rather than an ordinary nonzero exit.
+    pub fn timed_out(&self) -> bool {
+        self.shared.borrow().timed_out
+    }
+
+    pub fn metrics(&self) -> Vec<DeclaredMetric> {
+        self.shared.borrow().metrics.clone()
+    }
+
+    pub fn dependencies(&self) -> Vec<String> {
+        self.shared.borrow().dependencies.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn temp_work_dir(label: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("viaduct-buildenv-test-{}-{}", label, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn run_succeeds_on_a_zero_exit_command() {
+        let work_dir = temp_work_dir("ok");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let env = BuildEnv::new(&work_dir, &[], tx, None).unwrap();
+
+        env.exec("run('true')").await.unwrap();
+
+        assert!(env.failing_command().is_none());
+        assert!(!env.timed_out());
+
+        let _ = std::fs::remove_dir_all(&work_dir);
+    }
+
+    #[tokio::test]
+    async fn run_records_the_failing_command_on_nonzero_exit() {
+        let work_dir = temp_work_dir("fail");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let env = BuildEnv::new(&work_dir, &[], tx, None).unwrap();
+
+        let result = env.exec("run('exit 7')").await;
+
+        assert!(result.is_err());
+        let (index, command) = env.failing_command().expect("a failing command");
+        assert_eq!(index, 0);
+        assert_eq!(command, "exit 7");
+        assert!(!env.timed_out());
+
+        let _ = std::fs::remove_dir_all(&work_dir);
+    }
+
+    #[tokio::test]
+    async fn run_times_out_and_is_reported_distinctly_from_a_failing_command() {
+        let work_dir = temp_work_dir("timeout");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let env = BuildEnv::new(&work_dir, &[], tx, None).unwrap();
+
+        let result = env.exec("run('sleep 5', {timeout = 1})").await;
+
+        assert!(result.is_err());
+        assert!(env.timed_out());
+
+        let _ = std::fs::remove_dir_all(&work_dir);
+    }
+
+    #[tokio::test]
+    async fn artifact_metric_and_dependencies_are_registered() {
+        let work_dir = temp_work_dir("declare");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let env = BuildEnv::new(&work_dir, &[], tx, None).unwrap();
+
+        env.exec(
+            r#"
+            artifact("target/out.bin", "binary")
+            metric("coverage", "87%")
+            dependencies("rustc", "cargo")
+            "#,
+        )
+        .await
+        .unwrap();
+
+        let artifacts = env.artifacts();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].name, "binary");
+        assert_eq!(artifacts[0].path, "target/out.bin");
+
+        let metrics = env.metrics();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].name, "coverage");
+        assert_eq!(metrics[0].value, "87%");
+
+        assert_eq!(env.dependencies(), vec!["rustc".to_string(), "cargo".to_string()]);
+
+        let _ = std::fs::remove_dir_all(&work_dir);
+    }
+}
@@ -0,0 +1,170 @@
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::execute_job;
+use crate::job::Job;
+use crate::StreamEvent;
+
+/// A unit of work handed out by a driver's long-poll endpoint. `build_token`
+/// identifies this job to the driver so results can be reported back
+/// against the right request. `remote_url`/`commit` are the actual clone
+/// target and checkout the driver wants built, which may not match
+/// `job.repository`/`job.branch` as committed in the `job` sub-object (e.g.
+/// a PR build pinned to a specific commit rather than a branch's moving
+/// HEAD).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestedJob {
+    pub job: Job,
+    pub commit: String,
+    pub remote_url: String,
+    pub build_token: String,
+}
+
+/// Pull-mode counterpart to the `/job` push endpoint: instead of waiting
+/// for a driver to reach the worker, the worker opens a persistent
+/// connection outward and long-polls the driver for work. This lets a
+/// fleet of workers sit behind NAT/firewalls and scale horizontally
+/// against one driver.
+pub struct RunnerClient {
+    driver_host: String,
+    http: awc::Client,
+    current_job: Mutex<Option<String>>,
+}
+
+impl RunnerClient {
+    pub fn new(driver_host: impl Into<String>) -> Self {
+        Self {
+            driver_host: driver_host.into(),
+            http: awc::Client::default(),
+            current_job: Mutex::new(None),
+        }
+    }
+
+    /// Loops forever: acquire work, mark the worker busy, run the job
+    /// through the existing `execute_job` path while streaming results
+    /// back, then go idle and poll again.
+    pub async fn run(&self) {
+        loop {
+            match self.poll_for_job().await {
+                Ok(Some(requested)) => {
+                    *self.current_job.lock().unwrap() = Some(requested.job.name.clone());
+                    self.report_status(true).await;
+
+                    self.run_job(requested).await;
+
+                    *self.current_job.lock().unwrap() = None;
+                    self.report_status(false).await;
+                }
+                Ok(None) => {
+                    // Long-poll returned with no work ready; go straight
+                    // back to waiting.
+                }
+                Err(e) => {
+                    println!("{} Failed to poll driver: {}", "❌".red(), e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+
+    async fn poll_for_job(&self) -> Result<Option<RequestedJob>, awc::error::SendRequestError> {
+        let url = format!("{}/poll", self.driver_host);
+        let mut response = self.http.get(&url).send().await?;
+
+        if response.status() == awc::http::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+
+        match response.json::<RequestedJob>().await {
+            Ok(requested) => Ok(Some(requested)),
+            Err(e) => {
+                println!("{} Driver sent an unreadable job: {}", "❌".red(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn report_status(&self, busy: bool) {
+        let url = format!("{}/status", self.driver_host);
+        let current_job = self.current_job.lock().unwrap().clone();
+        let body = serde_json::json!({ "busy": busy, "current_job": current_job });
+        if let Err(e) = self.http.post(&url).send_json(&body).await {
+            println!("{} Failed to report status to driver: {}", "❌".red(), e);
+        }
+    }
+
+    /// Runs one requested job, forwarding every `StreamEvent` it produces
+    /// back to the driver as it happens rather than waiting for the whole
+    /// build to finish.
+    async fn run_job(&self, requested: RequestedJob) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<StreamEvent>();
+        let events_url = format!("{}/jobs/{}/events", self.driver_host, requested.build_token);
+        let http = self.http.clone();
+
+        let reporter = tokio::task::spawn_local(async move {
+            while let Some(event) = rx.recv().await {
+                if let Err(e) = http.post(&events_url).send_json(&event).await {
+                    println!("{} Failed to report job event to driver: {}", "❌".red(), e);
+                }
+            }
+        });
+
+        let job = prepare_job(requested);
+
+        let result = execute_job(&job, tx.clone()).await;
+        let _ = tx.send(StreamEvent::Done { result: result.clone() });
+        drop(tx);
+        let _ = reporter.await;
+
+        println!("{} Reported result for {}: {:?}", "📤".blue(), job.name, result);
+    }
+}
+
+/// Turns a `RequestedJob` from the driver into the `Job` `execute_job` will
+/// actually build: the clone target and checkout are the driver's
+/// `remote_url`/`commit`, not whatever `job.repository`/`job.branch` says,
+/// and `build_token` is threaded through so the result can be reported back
+/// against the right request.
+fn prepare_job(requested: RequestedJob) -> Job {
+    let mut job = requested.job;
+    job.build_token = Some(requested.build_token);
+    job.repository = requested.remote_url;
+    job.commit = Some(requested.commit);
+    job
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_requested_job() -> RequestedJob {
+        RequestedJob {
+            job: Job {
+                name: "build".to_string(),
+                repository: "https://example.com/stale.git".to_string(),
+                branch: "main".to_string(),
+                commands: vec![],
+                inputs: vec![],
+                outputs: vec![],
+                build_token: None,
+                timeout_secs: None,
+                commit: None,
+            },
+            commit: "abc123".to_string(),
+            remote_url: "https://example.com/actual.git".to_string(),
+            build_token: "token-1".to_string(),
+        }
+    }
+
+    #[test]
+    fn prepare_job_checks_out_the_requested_commit_against_remote_url() {
+        let job = prepare_job(sample_requested_job());
+
+        assert_eq!(job.repository, "https://example.com/actual.git");
+        assert_eq!(job.commit, Some("abc123".to_string()));
+        assert_eq!(job.build_token, Some("token-1".to_string()));
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub name: String,
+    pub repository: String,
+    pub branch: String,
+    pub commands: Vec<String>,
+    #[serde(default)]
+    pub inputs: Vec<JobInput>,
+    #[serde(default)]
+    pub outputs: Vec<JobOutput>,
+    /// Identifies the request that authorized this job, so a result can be
+    /// tied back to whoever signed it in.
+    #[serde(default)]
+    pub build_token: Option<String>,
+    /// Whole-build time budget in seconds. A goodfile step can further
+    /// tighten this with its own `run(cmd, {timeout = ...})` override, but
+    /// neither a single step nor the build as a whole may outlive this.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Exact commit to check out after cloning, when the caller needs more
+    /// than "whatever HEAD of `branch` happens to be at clone time" — set
+    /// by pull-mode's `RunnerClient` from the driver's `RequestedJob.commit`.
+    #[serde(default)]
+    pub commit: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInput {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobOutput {
+    pub name: String,
+    pub path: String,
+}
+
+/// One `metric(name, value)` declared by a goodfile, reported back so a
+/// driver can track build-over-build trends (binary size, test counts, ...).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobMetric {
+    pub name: String,
+    pub value: String,
+}
+
+/// Metadata for one collected artifact. The bytes themselves live on disk
+/// under `artifacts/<path>` (`path` being `<job_id>/<name>`) and are fetched separately via
+/// `GET /artifacts/{job}/{name}` rather than embedded here, so binary
+/// outputs don't need to round-trip through JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobArtifact {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub content_hash: String,
+}
+
+/// Where a job is in its lifecycle, independent of whether it ultimately
+/// passed or failed. A caller polling a job should use this to decide
+/// whether to keep waiting, retry, or look at `Outcome`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Pending,
+    Running,
+    Finished,
+    Error,
+}
+
+/// What happened once a job reached a terminal state. `TestFailure` means
+/// the build genuinely ran and a command came back nonzero; `InfraError`
+/// means the worker couldn't get far enough to judge the build at all
+/// (clone failed, couldn't create a work dir, timed out). Callers should
+/// treat the latter as retryable and the former as a real result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    Pass,
+    TestFailure {
+        command_index: usize,
+        command: String,
+        desc: String,
+    },
+    InfraError {
+        desc: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobResult {
+    pub id: String,
+    pub state: JobState,
+    pub outcome: Outcome,
+    pub output: String,
+    pub artifacts: Vec<JobArtifact>,
+    pub metrics: Vec<JobMetric>,
+    pub build_token: Option<String>,
+}
@@ -1,155 +1,324 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use serde::{Deserialize, Serialize};
+mod artifacts;
+mod auth;
+mod buildenv;
+mod client;
+mod job;
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use serde::Serialize;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::process::Command;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
 use uuid::Uuid;
-use std::process::Command;
-use std::fs;
 use colored::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Job {
-    name: String,
-    repository: String,
-    branch: String,
-    commands: Vec<String>,
-    #[serde(default)]
-    inputs: Vec<JobInput>,
-    #[serde(default)]
-    outputs: Vec<JobOutput>,
-}
+use auth::{PreSharedKeys, VerifiedJob, SIGNATURE_HEADER};
+use buildenv::BuildEnv;
+use job::{Job, JobArtifact, JobResult, JobState, Outcome};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct JobInput {
-    name: String,
-    value: String,
-}
+/// Name of the repo-committed build script `execute_job` looks for at the
+/// root of the freshly cloned repository.
+const GOODFILE_NAME: &str = "goodfile";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct JobOutput {
-    name: String,
-    path: String,
-}
+/// Clone timeout used when a job doesn't set `timeout_secs`. Matches the
+/// "a clone against a dead remote shouldn't wedge the worker forever" goal
+/// that also motivates the goodfile step/build timeouts in `buildenv`.
+const DEFAULT_CLONE_TIMEOUT_SECS: u64 = 300;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct JobResult {
-    id: String,
-    status: String,
-    output: String,
-    artifacts: Vec<JobArtifact>,
+/// One chunk of the `/job` response stream. Sent as newline-delimited JSON
+/// so a client sees log lines and artifacts as they're produced, with the
+/// final `JobResult` closing out the stream instead of the whole response
+/// only appearing once the build is finished.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum StreamEvent {
+    State { state: JobState },
+    Log { line: String },
+    Artifact { artifact: JobArtifact },
+    Done { result: JobResult },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct JobArtifact {
-    name: String,
-    content: String,
+/// Streams a previously-collected artifact back to the client. The file
+/// lives at `artifacts/<job>/<name>` as written by `artifacts::collect`.
+///
+/// Requires the same HMAC signature scheme as `/job` (signed over
+/// `<job>/<name>`), since an artifact can contain anything a build produced
+/// and this worker may be reachable from an untrusted network.
+async fn get_artifact(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    psks: web::Data<PreSharedKeys>,
+) -> impl Responder {
+    let (job_id, name) = path.into_inner();
+
+    let signature = match req.headers().get(SIGNATURE_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(signature) => signature.to_string(),
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let signed_payload = format!("{}/{}", job_id, name);
+    if !psks.verify(signed_payload.as_bytes(), &signature) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let file_path = match artifacts::resolve_within_root(std::path::Path::new(artifacts::ARTIFACTS_ROOT), &job_id, &name).await {
+        Ok(path) => path,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+
+    match tokio::fs::File::open(&file_path).await {
+        Ok(file) => {
+            let stream = tokio_util::io::ReaderStream::new(file)
+                .map(|chunk| chunk.map_err(actix_web::Error::from));
+            HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .streaming(stream)
+        }
+        Err(_) => HttpResponse::NotFound().finish(),
+    }
 }
 
-async fn process_job(job: web::Json<Job>) -> impl Responder {
+async fn process_job(job: VerifiedJob) -> impl Responder {
+    let job = job.0;
     println!("{} Received job: {:?}", "🛎️".green(), job);
-    
-    let result = execute_job(&job);
-    
-    println!("{} Sending result: {:?}", "📤".blue(), result);
-    
-    HttpResponse::Ok().json(result)
+
+    let (tx, rx) = mpsc::unbounded_channel::<StreamEvent>();
+
+    actix_web::rt::spawn(async move {
+        let _ = tx.send(StreamEvent::State { state: JobState::Running });
+        let result = execute_job(&job, tx.clone()).await;
+        println!("{} Sending result: {:?}", "📤".blue(), result);
+        let _ = tx.send(StreamEvent::Done { result });
+    });
+
+    let stream = UnboundedReceiverStream::new(rx).map(|event| {
+        let mut line = serde_json::to_string(&event).unwrap_or_default();
+        line.push('\n');
+        Ok::<_, actix_web::Error>(web::Bytes::from(line))
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(stream)
 }
 
-fn execute_job(job: &Job) -> JobResult {
-    let work_dir = format!("work_{}", Uuid::new_v4());
-    fs::create_dir(&work_dir).expect(&format!("{} Failed to create work directory", "❌".red()));
+pub(crate) async fn execute_job(job: &Job, tx: UnboundedSender<StreamEvent>) -> JobResult {
+    let job_id = Uuid::new_v4().to_string();
+    let work_dir = format!("work_{}", job_id);
+    if let Err(e) = fs::create_dir(&work_dir).await {
+        let desc = format!("failed to create work directory: {}", e);
+        let output = format!("{} {}", "❌".red(), desc);
+        let _ = tx.send(StreamEvent::Log { line: output.clone() });
+        return JobResult {
+            id: job_id,
+            state: JobState::Error,
+            outcome: Outcome::InfraError { desc },
+            output,
+            artifacts: Vec::new(),
+            metrics: Vec::new(),
+            build_token: job.build_token.clone(),
+        };
+    }
+
+    let artifacts_dir = artifacts::reserve_artifacts_dir(&job_id)
+        .await
+        .unwrap_or_else(|_| panic!("{} Failed to reserve artifacts directory", "❌".red()));
 
     let mut output = String::new();
-    let mut status = "success".to_string();
+    let mut outcome = Outcome::Pass;
     let mut artifacts = Vec::new();
+    let mut metrics = Vec::new();
 
-    // Clone repository
+    // Clone repository. Subject to the same whole-build timeout as the
+    // goodfile itself (job.timeout_secs), so a clone against a dead remote
+    // can't wedge the worker forever the way it could before this budget
+    // was applied here too.
+    let build_started = Instant::now();
+    let clone_timeout = Duration::from_secs(job.timeout_secs.unwrap_or(DEFAULT_CLONE_TIMEOUT_SECS));
     println!("{} Cloning repository: {}", "🔄".yellow(), job.repository);
-    let clone_result = Command::new("git")
-        .args(&["clone", "-b", &job.branch, &job.repository, &work_dir])
-        .output();
-
-    match clone_result {
-        Ok(clone_output) => {
-            if !clone_output.status.success() {
-                status = "failed".to_string();
-                output = format!("{} Failed to clone repository: {}", "❌".red(), String::from_utf8_lossy(&clone_output.stderr));
-            } else {
-                println!("{} Repository cloned successfully", "✅".green());
-            }
-        }
-        Err(e) => {
-            status = "failed".to_string();
-            output = format!("{} Error cloning repository: {}", "❌".red(), e);
-        }
-    }
+    let mut clone_command = Command::new("git");
+    clone_command
+        .args(["clone", "-b", &job.branch, &job.repository, &work_dir])
+        .process_group(0)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
 
-    // Execute commands if cloning was successful
-    if status == "success" {
-        for (i, cmd) in job.commands.iter().enumerate() {
-            println!("{} Executing command {}/{}: {}", "🚀".cyan(), i+1, job.commands.len(), cmd);
-            let cmd_result = Command::new("sh")
-                .arg("-c")
-                .arg(cmd)
-                .current_dir(&work_dir)
-                .output();
-
-            match cmd_result {
-                Ok(cmd_output) => {
-                    output.push_str(&format!("{} Command: {}\n", "🖥️".blue(), cmd));
-                    output.push_str(&String::from_utf8_lossy(&cmd_output.stdout));
-                    output.push_str(&String::from_utf8_lossy(&cmd_output.stderr));
-                    
-                    if !cmd_output.status.success() {
-                        status = "failed".to_string();
-                        println!("{} Command failed", "❌".red());
-                        break;
+    match clone_command.spawn() {
+        Ok(child) => {
+            let pid = child.id().map(|id| id as i32);
+            match tokio::time::timeout(clone_timeout, child.wait_with_output()).await {
+                Ok(Ok(clone_output)) => {
+                    if !clone_output.status.success() {
+                        let desc = format!("failed to clone repository: {}", String::from_utf8_lossy(&clone_output.stderr));
+                        output = format!("{} {}", "❌".red(), desc);
+                        let _ = tx.send(StreamEvent::Log { line: output.clone() });
+                        outcome = Outcome::InfraError { desc };
                     } else {
-                        println!("{} Command executed successfully", "✅".green());
+                        println!("{} Repository cloned successfully", "✅".green());
+
+                        // Pull-mode jobs pin an exact commit (set by
+                        // RunnerClient from the driver's RequestedJob) rather
+                        // than trusting whatever HEAD of `branch` happens to
+                        // be at clone time.
+                        if let Some(commit) = &job.commit {
+                            println!("{} Checking out commit: {}", "🔄".yellow(), commit);
+                            match Command::new("git").args(["checkout", commit]).current_dir(&work_dir).output().await {
+                                Ok(checkout_output) if checkout_output.status.success() => {
+                                    println!("{} Checked out {}", "✅".green(), commit);
+                                }
+                                Ok(checkout_output) => {
+                                    let desc = format!(
+                                        "failed to check out commit {}: {}",
+                                        commit,
+                                        String::from_utf8_lossy(&checkout_output.stderr)
+                                    );
+                                    output = format!("{} {}", "❌".red(), desc);
+                                    let _ = tx.send(StreamEvent::Log { line: output.clone() });
+                                    outcome = Outcome::InfraError { desc };
+                                }
+                                Err(e) => {
+                                    let desc = format!("error checking out commit {}: {}", commit, e);
+                                    output = format!("{} {}", "❌".red(), desc);
+                                    let _ = tx.send(StreamEvent::Log { line: output.clone() });
+                                    outcome = Outcome::InfraError { desc };
+                                }
+                            }
+                        }
                     }
                 }
-                Err(e) => {
-                    status = "failed".to_string();
-                    output.push_str(&format!("{} Error executing command: {}\n", "❌".red(), e));
-                    println!("{} Error executing command: {}", "❌".red(), e);
-                    break;
+                Ok(Err(e)) => {
+                    let desc = format!("error cloning repository: {}", e);
+                    output = format!("{} {}", "❌".red(), desc);
+                    let _ = tx.send(StreamEvent::Log { line: output.clone() });
+                    outcome = Outcome::InfraError { desc };
+                }
+                Err(_) => {
+                    if let Some(pid) = pid {
+                        buildenv::kill_process_group(pid);
+                    }
+                    let desc = format!("cloning {} timed out after {}s", job.repository, clone_timeout.as_secs());
+                    output = format!("{} {}", "❌".red(), desc);
+                    let _ = tx.send(StreamEvent::Log { line: output.clone() });
+                    outcome = Outcome::InfraError { desc };
                 }
             }
         }
+        Err(e) => {
+            let desc = format!("failed to spawn git clone: {}", e);
+            output = format!("{} {}", "❌".red(), desc);
+            let _ = tx.send(StreamEvent::Log { line: output.clone() });
+            outcome = Outcome::InfraError { desc };
+        }
+    }
 
-        // Collect artifacts
-        println!("{} Collecting artifacts", "📦".magenta());
-        for output_spec in &job.outputs {
-            let path = format!("{}/{}", work_dir, output_spec.path);
-            match fs::read_to_string(&path) {
-                Ok(content) => {
-                    artifacts.push(JobArtifact {
-                        name: output_spec.name.clone(),
-                        content,
-                    });
-                    println!("{} Artifact collected: {}", "✅".green(), output_spec.name);
-                }
-                Err(e) => {
-                    output.push_str(&format!("{} Error reading output {}: {}\n", "❌".red(), output_spec.name, e));
-                    println!("{} Error reading artifact {}: {}", "❌".red(), output_spec.name, e);
+    // Run the goodfile if cloning was successful
+    if matches!(outcome, Outcome::Pass) {
+        let goodfile_path = format!("{}/{}", work_dir, GOODFILE_NAME);
+        let goodfile = fs::read_to_string(&goodfile_path).await.unwrap_or_else(|_| {
+            println!("{} No goodfile found, falling back to job.commands", "ℹ️".yellow());
+            buildenv::DEFAULT_GOODFILE.to_string()
+        });
+
+        let remaining_secs = job
+            .timeout_secs
+            .map(|secs| secs.saturating_sub(build_started.elapsed().as_secs()));
+        match BuildEnv::new(&work_dir, &job.inputs, tx.clone(), remaining_secs) {
+            Ok(env) => {
+                if let Err(e) = env.set_commands(&job.commands) {
+                    let desc = format!("failed to prepare build environment: {}", e);
+                    output = format!("{} {}", "❌".red(), desc);
+                    outcome = Outcome::InfraError { desc };
+                } else {
+                    println!("{} Running goodfile", "🚀".cyan());
+                    match env.exec(&goodfile).await {
+                        Ok(()) => {
+                            println!("{} Goodfile completed successfully", "✅".green());
+
+                            // Collect artifacts declared via artifact(path, name),
+                            // plus any still declared the old way via job.outputs.
+                            println!("{} Collecting artifacts", "📦".magenta());
+                            let declared = env
+                                .artifacts()
+                                .into_iter()
+                                .map(|a| (a.name, a.path))
+                                .chain(job.outputs.iter().map(|o| (o.name.clone(), o.path.clone())));
+                            for (name, path) in declared {
+                                for artifact in artifacts::collect(&work_dir, &artifacts_dir, &name, &path).await {
+                                    let _ = tx.send(StreamEvent::Artifact { artifact: artifact.clone() });
+                                    artifacts.push(artifact);
+                                }
+                            }
+
+                            let dependencies = env.dependencies();
+                            if !dependencies.is_empty() {
+                                println!("{} Declared dependencies: {}", "🧰".cyan(), dependencies.join(", "));
+                            }
+
+                            metrics = env
+                                .metrics()
+                                .into_iter()
+                                .map(|m| job::JobMetric { name: m.name, value: m.value })
+                                .collect();
+                            for metric in &metrics {
+                                println!("{} Metric: {} = {}", "📈".magenta(), metric.name, metric.value);
+                            }
+                        }
+                        Err(e) => {
+                            output = format!("{} Goodfile failed: {}", "❌".red(), e);
+                            println!("{}", output);
+                            let _ = tx.send(StreamEvent::Log { line: output.clone() });
+                            outcome = if env.timed_out() {
+                                Outcome::InfraError { desc: e.to_string() }
+                            } else {
+                                match env.failing_command() {
+                                    Some((command_index, command)) => Outcome::TestFailure {
+                                        command_index,
+                                        command,
+                                        desc: e.to_string(),
+                                    },
+                                    None => Outcome::InfraError { desc: format!("goodfile error: {}", e) },
+                                }
+                            };
+                        }
+                    }
                 }
             }
+            Err(e) => {
+                let desc = format!("failed to start build environment: {}", e);
+                output = format!("{} {}", "❌".red(), desc);
+                outcome = Outcome::InfraError { desc };
+            }
         }
     }
 
     // Cleanup
     println!("{} Cleaning up work directory", "🧹".yellow());
-    fs::remove_dir_all(&work_dir).expect(&format!("{} Failed to remove work directory", "❌".red()));
+    fs::remove_dir_all(&work_dir)
+        .await
+        .unwrap_or_else(|_| panic!("{} Failed to remove work directory", "❌".red()));
+
+    let state = match outcome {
+        Outcome::InfraError { .. } => JobState::Error,
+        Outcome::Pass | Outcome::TestFailure { .. } => JobState::Finished,
+    };
 
     let result = JobResult {
-        id: Uuid::new_v4().to_string(),
-        status: status.clone(),
+        id: job_id,
+        state,
+        outcome,
         output,
         artifacts,
+        metrics,
+        build_token: job.build_token.clone(),
     };
 
-    if status == "success" {
-        println!("{} Job completed successfully", "🎉".green());
-    } else {
-        println!("{} Job failed", "💔".red());
+    match &result.outcome {
+        Outcome::Pass => println!("{} Job completed successfully", "🎉".green()),
+        Outcome::TestFailure { .. } => println!("{} Job ran but a command failed", "💔".red()),
+        Outcome::InfraError { .. } => println!("{} Job could not be completed", "💔".red()),
     }
 
     result
@@ -157,10 +326,24 @@ fn execute_job(job: &Job) -> JobResult {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    println!("{} Starting CI/CD worker server", "🚀".green());
-    HttpServer::new(|| {
+    println!("{} Starting CI/CD worker", "🚀".green());
+
+    // VIADUCT_DRIVER switches the worker from hosting /job to long-polling
+    // a driver for work, which lets workers sit behind NAT/firewalls.
+    if let Ok(driver_host) = std::env::var("VIADUCT_DRIVER") {
+        println!("{} Pull mode: polling driver at {}", "🔌".cyan(), driver_host);
+        let client = client::RunnerClient::new(driver_host);
+        client.run().await;
+        return Ok(());
+    }
+
+    println!("{} Push mode: listening for jobs on 0.0.0.0:8080", "🛰️".cyan());
+    let psks = web::Data::new(auth::PreSharedKeys::from_env());
+    HttpServer::new(move || {
         App::new()
+            .app_data(psks.clone())
             .route("/job", web::post().to(process_job))
+            .route("/artifacts/{job}/{name}", web::get().to(get_artifact))
     })
     .bind("0.0.0.0:8080")?
     .run()